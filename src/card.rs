@@ -8,15 +8,18 @@
 
 //! Cards, decks, and their components.
 
+pub mod score;
+
 use std::fmt;
 
 use rand::seq::SliceRandom;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 /// A card suit.
 ///
 /// A card has one of four suits.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Suit {
     Spade,
     Heart,
@@ -39,7 +42,7 @@ impl Suit {
 ///
 /// A card has one of thirteen ranks, starting at [`Ace`](enum.Rank.html#variant.Ace) up to
 /// [`King`](enum.Rank.html#variant.King).
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Rank {
     Ace,
     Two,
@@ -79,10 +82,38 @@ impl Rank {
     pub fn iter() -> impl Iterator<Item = Rank> {
         RANKS.iter().cloned()
     }
+
+    /// Return the pip value of this rank, as used for counting to fifteen.
+    ///
+    /// Ace is 1, Two through Nine are their face value, and Ten, Jack, Queen, and King are all
+    /// worth 10.
+    pub fn pip_value(self) -> u32 {
+        use Rank::*;
+
+        match self {
+            Ace => 1,
+            Two => 2,
+            Three => 3,
+            Four => 4,
+            Five => 5,
+            Six => 6,
+            Seven => 7,
+            Eight => 8,
+            Nine => 9,
+            Ten | Jack | Queen | King => 10,
+        }
+    }
+
+    /// Return the ordinal position of this rank, from `Ace` (1) to `King` (13).
+    ///
+    /// This is used to detect runs, which do not wrap from `King` back to `Ace`.
+    pub fn ordinal(self) -> u32 {
+        self as u32 + 1
+    }
 }
 
 /// A card, which is a combination of a suit and a rank.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Card {
     pub suit: Suit,
     pub rank: Rank,
@@ -162,6 +193,33 @@ static CARD_DISPLAY: [[char; 13]; 4] = [
     ],
 ];
 
+/// An error produced when a [`Deck`] does not hold enough cards to do what was asked of it.
+#[derive(Debug, Eq, PartialEq)]
+pub enum DealError {
+    /// `available` cards remained in the deck, but `needed` were required.
+    NotEnoughCards { needed: usize, available: usize },
+
+    /// Cribbage is not played with this many players.
+    UnsupportedPlayerCount(usize),
+}
+
+impl fmt::Display for DealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DealError::NotEnoughCards { needed, available } => write!(
+                f,
+                "not enough cards to deal: needed {}, only {} remain",
+                needed, available
+            ),
+            DealError::UnsupportedPlayerCount(players) => {
+                write!(f, "cribbage is not played with {} players", players)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DealError {}
+
 /// A deck of cards.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Deck(Vec<Card>);
@@ -206,35 +264,44 @@ impl Deck {
         &self.0
     }
 
-    /// Deal out two hands.
-    pub fn deal(&mut self) -> (Hand, Hand) {
-        let mut dealer = Vec::with_capacity(6);
-        let mut opponent = Vec::with_capacity(6);
-
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-        opponent.push(self.0.pop().unwrap());
-        dealer.push(self.0.pop().unwrap());
-
-        (
-            Hand {
-                unplayed: dealer,
-                played: Vec::with_capacity(6),
-            },
+    /// Deal hands to `players` players, following the standard layout for that player count.
+    ///
+    /// Two players receive six cards each; three players receive five each, plus one card dealt
+    /// directly to the crib to keep it a fair size; four players, playing in two partnerships,
+    /// receive five each. Returns the dealt hands and whatever cards were dealt straight to the
+    /// crib, or an error if the deck does not hold enough cards.
+    pub fn deal_n(&mut self, players: usize) -> Result<(Vec<Hand>, Vec<Card>), DealError> {
+        let (hand_size, crib_cards) = match players {
+            2 => (6, 0),
+            3 => (5, 1),
+            4 => (5, 0),
+            _ => return Err(DealError::UnsupportedPlayerCount(players)),
+        };
 
-            Hand {
-                unplayed: opponent,
-                played: Vec::with_capacity(6),
-            },
-        )
+        let needed = players * hand_size + crib_cards;
+        if self.0.len() < needed {
+            return Err(DealError::NotEnoughCards {
+                needed,
+                available: self.0.len(),
+            });
+        }
+
+        let mut hands = Vec::with_capacity(players);
+        for _ in 0..players {
+            let mut unplayed = Vec::with_capacity(hand_size);
+            for _ in 0..hand_size {
+                unplayed.push(self.0.pop().unwrap());
+            }
+
+            hands.push(Hand {
+                unplayed,
+                played: Vec::with_capacity(hand_size),
+            });
+        }
+
+        let crib = (0..crib_cards).map(|_| self.0.pop().unwrap()).collect();
+
+        Ok((hands, crib))
     }
 
     /// Cut the deck randomly and return the cut card.
@@ -245,6 +312,35 @@ impl Deck {
         let index: usize = rng.gen_range(0, self.0.len());
         self.0.remove(index)
     }
+
+    /// Cut for dealer: each of `players` players draws one card, and the player who drew the
+    /// lowest rank (Ace low) is returned as the dealer's index.
+    ///
+    /// Ties are broken by reshuffling the whole deck and drawing again, mirroring the
+    /// draw-for-button mechanic used to seat players in trick-taking games.
+    pub fn cut_for_dealer<R>(&mut self, players: usize, rng: &mut R) -> usize
+    where
+        R: Rng,
+    {
+        assert!(
+            self.0.len() >= players,
+            "not enough cards in the deck to cut for dealer"
+        );
+
+        loop {
+            self.shuffle(rng);
+            let draws = &self.0[self.0.len() - players..];
+
+            let lowest_ordinal = draws.iter().map(|c| c.rank.ordinal()).min().unwrap();
+            let lowest: Vec<usize> = (0..players)
+                .filter(|&i| draws[i].rank.ordinal() == lowest_ordinal)
+                .collect();
+
+            if lowest.len() == 1 {
+                return lowest[0];
+            }
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -259,6 +355,104 @@ impl Hand {
     }
 }
 
+/// A set of cards backed by a 52-bit mask, one bit per suit-rank combination.
+///
+/// Each card occupies the bit at `suit as usize * 13 + rank as usize`, the same indexing scheme
+/// used by [`CARD_DISPLAY`]. Unlike [`Deck`] and [`Hand`], a `CardSet` has no notion of order; it
+/// exists so that the scoring engine and AI can enumerate the many card combinations a cribbage
+/// turn requires (e.g. the 15 keep/crib splits of a 6-card hand, each scored against all 46
+/// possible starters) without repeatedly cloning `Vec<Card>`s.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CardSet(u64);
+
+impl CardSet {
+    /// Return a new, empty card set.
+    pub fn new() -> Self {
+        CardSet(0)
+    }
+
+    fn bit(card: Card) -> u64 {
+        1u64 << (card.suit as usize * 13 + card.rank as usize)
+    }
+
+    /// Insert a card into the set.
+    pub fn insert(&mut self, card: Card) {
+        self.0 |= Self::bit(card);
+    }
+
+    /// Remove a card from the set.
+    pub fn remove(&mut self, card: Card) {
+        self.0 &= !Self::bit(card);
+    }
+
+    /// Return whether the set contains the given card.
+    pub fn contains(&self, card: Card) -> bool {
+        self.0 & Self::bit(card) != 0
+    }
+
+    /// Return the number of cards in the set.
+    pub fn len(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Return whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Return the union of this set and `other`.
+    pub fn union(&self, other: &CardSet) -> CardSet {
+        CardSet(self.0 | other.0)
+    }
+
+    /// Return the intersection of this set and `other`.
+    pub fn intersection(&self, other: &CardSet) -> CardSet {
+        CardSet(self.0 & other.0)
+    }
+
+    /// Return the cards in this set that are not in `other`.
+    pub fn difference(&self, other: &CardSet) -> CardSet {
+        CardSet(self.0 & !other.0)
+    }
+
+    /// Return an iterator over the cards in the set.
+    pub fn iter(&self) -> impl Iterator<Item = Card> {
+        let mut bits = self.0;
+
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                None
+            } else {
+                let index = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+
+                Some(Card {
+                    suit: SUITS[index / 13],
+                    rank: RANKS[index % 13],
+                })
+            }
+        })
+    }
+}
+
+impl From<&[Card]> for CardSet {
+    fn from(cards: &[Card]) -> Self {
+        let mut set = CardSet::new();
+
+        for &card in cards {
+            set.insert(card);
+        }
+
+        set
+    }
+}
+
+impl From<&Hand> for CardSet {
+    fn from(hand: &Hand) -> Self {
+        CardSet::from(hand.cards())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -482,4 +676,110 @@ mod test {
             ])
         );
     }
+
+    #[test]
+    fn card_set_insert_remove_contains() {
+        use Rank::*;
+        use Suit::*;
+
+        let ace_of_spades = Card {
+            suit: Spade,
+            rank: Ace,
+        };
+        let king_of_clubs = Card {
+            suit: Club,
+            rank: King,
+        };
+
+        let mut set = CardSet::new();
+        assert!(set.is_empty());
+
+        set.insert(ace_of_spades);
+        set.insert(king_of_clubs);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(ace_of_spades));
+        assert!(set.contains(king_of_clubs));
+
+        set.remove(ace_of_spades);
+        assert_eq!(set.len(), 1);
+        assert!(!set.contains(ace_of_spades));
+    }
+
+    #[test]
+    fn card_set_operations_and_conversions() {
+        use Rank::*;
+        use Suit::*;
+
+        let hand = [
+            Card {
+                suit: Spade,
+                rank: Ace,
+            },
+            Card {
+                suit: Heart,
+                rank: Two,
+            },
+        ];
+
+        let set = CardSet::from(&hand[..]);
+        assert_eq!(set.len(), 2);
+
+        let mut only_ace = CardSet::new();
+        only_ace.insert(hand[0]);
+
+        assert_eq!(set.intersection(&only_ace), only_ace);
+        assert_eq!(set.difference(&only_ace).len(), 1);
+        assert_eq!(only_ace.union(&set), set);
+
+        let mut cards: Vec<Card> = set.iter().collect();
+        cards.sort_by_key(|c| (c.suit as usize, c.rank as usize));
+        assert_eq!(cards, vec![hand[0], hand[1]]);
+    }
+
+    #[test]
+    fn deal_n_follows_the_standard_layout_per_player_count() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+
+        let (hands, crib) = Deck::new_shuffled(&mut rng).deal_n(2).unwrap();
+        assert_eq!(hands.len(), 2);
+        assert!(hands.iter().all(|h| h.cards().len() == 6));
+        assert!(crib.is_empty());
+
+        let (hands, crib) = Deck::new_shuffled(&mut rng).deal_n(3).unwrap();
+        assert_eq!(hands.len(), 3);
+        assert!(hands.iter().all(|h| h.cards().len() == 5));
+        assert_eq!(crib.len(), 1);
+
+        let (hands, crib) = Deck::new_shuffled(&mut rng).deal_n(4).unwrap();
+        assert_eq!(hands.len(), 4);
+        assert!(hands.iter().all(|h| h.cards().len() == 5));
+        assert!(crib.is_empty());
+    }
+
+    #[test]
+    fn deal_n_rejects_unsupported_player_counts() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+
+        assert_eq!(
+            Deck::new_shuffled(&mut rng).deal_n(5),
+            Err(DealError::UnsupportedPlayerCount(5))
+        );
+    }
+
+    #[test]
+    fn cut_for_dealer_picks_the_lowest_rank() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut deck = Deck::new_shuffled(&mut rng);
+
+        let dealer = deck.cut_for_dealer(4, &mut rng);
+        assert!(dealer < 4);
+
+        // The draws are the top four cards, which cutting for dealer leaves in the deck.
+        let draws = &deck.cards()[deck.cards().len() - 4..];
+        for (i, drawn) in draws.iter().enumerate() {
+            if i != dealer {
+                assert!(drawn.rank.ordinal() > draws[dealer].rank.ordinal());
+            }
+        }
+    }
 }
@@ -0,0 +1,225 @@
+// Copyright 2019 Barret Rennie
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scoring a cribbage hand.
+
+use super::{Card, Rank};
+
+/// Score a 4-card hand together with the starter card.
+///
+/// `hand` is the set of cards held (or, for the crib, the cards given by both players) and
+/// `starter` is the card cut after the deal. `is_crib` changes the scoring of flushes: a crib
+/// flush only counts when the starter matches the suit of the other four cards.
+pub fn score_hand(hand: &[Card; 4], starter: Card, is_crib: bool) -> u32 {
+    let mut cards = [hand[0], hand[1], hand[2], hand[3], starter];
+    cards.sort_by_key(|c| c.rank.ordinal());
+
+    score_fifteens(&cards)
+        + score_pairs(&cards)
+        + score_runs(&cards)
+        + score_flush(hand, starter, is_crib)
+        + score_nobs(hand, starter)
+}
+
+/// Score 2 points for every subset of the 5 cards whose pip values sum to 15.
+fn score_fifteens(cards: &[Card; 5]) -> u32 {
+    let mut points = 0;
+
+    for subset in 1u32..(1 << 5) {
+        let sum: u32 = (0..5)
+            .filter(|i| subset & (1 << i) != 0)
+            .map(|i| cards[i].rank.pip_value())
+            .sum();
+
+        if sum == 15 {
+            points += 2;
+        }
+    }
+
+    points
+}
+
+/// Score 2 points for every unordered pair of cards sharing a rank.
+fn score_pairs(cards: &[Card; 5]) -> u32 {
+    let mut points = 0;
+
+    for i in 0..5 {
+        for j in (i + 1)..5 {
+            if cards[i].rank == cards[j].rank {
+                points += 2;
+            }
+        }
+    }
+
+    points
+}
+
+/// Score the longest run of 3 or more consecutive ranks, multiplied by the number of ways it can
+/// be formed from duplicate ranks.
+fn score_runs(cards: &[Card; 5]) -> u32 {
+    let mut ordinals: Vec<u32> = cards.iter().map(|c| c.rank.ordinal()).collect();
+    ordinals.sort();
+    ordinals.dedup_by(|a, b| a == b);
+
+    let mut best_len = 0;
+    let mut best_product = 0;
+
+    let mut start = 0;
+    while start < ordinals.len() {
+        let mut end = start;
+        while end + 1 < ordinals.len() && ordinals[end + 1] == ordinals[end] + 1 {
+            end += 1;
+        }
+
+        let len = end - start + 1;
+        if len >= 3 && len > best_len {
+            best_len = len;
+            best_product = ordinals[start..=end]
+                .iter()
+                .map(|&rank| cards.iter().filter(|c| c.rank.ordinal() == rank).count() as u32)
+                .product();
+        }
+
+        start = end + 1;
+    }
+
+    best_len as u32 * best_product
+}
+
+/// Score a flush: 4 points if the hand shares a suit, 5 if the starter matches too. In the crib,
+/// only the 5-card flush counts.
+fn score_flush(hand: &[Card; 4], starter: Card, is_crib: bool) -> u32 {
+    let suit = hand[0].suit;
+
+    if !hand.iter().all(|c| c.suit == suit) {
+        return 0;
+    }
+
+    if starter.suit == suit {
+        5
+    } else if is_crib {
+        0
+    } else {
+        4
+    }
+}
+
+/// Score "nobs": 1 point if the hand holds the jack matching the starter's suit.
+fn score_nobs(hand: &[Card; 4], starter: Card) -> u32 {
+    if hand
+        .iter()
+        .any(|c| c.rank == Rank::Jack && c.suit == starter.suit)
+    {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::card::Suit;
+
+    #[test]
+    fn the_perfect_hand() {
+        use Rank::*;
+        use Suit::*;
+
+        let hand = [
+            Card {
+                suit: Spade,
+                rank: Five,
+            },
+            Card {
+                suit: Club,
+                rank: Five,
+            },
+            Card {
+                suit: Diamond,
+                rank: Five,
+            },
+            Card {
+                suit: Heart,
+                rank: Jack,
+            },
+        ];
+        let starter = Card {
+            suit: Heart,
+            rank: Five,
+        };
+
+        assert_eq!(score_hand(&hand, starter, false), 29);
+    }
+
+    #[test]
+    fn flush_does_not_count_in_crib_unless_starter_matches() {
+        use Rank::*;
+        use Suit::*;
+
+        let hand = [
+            Card {
+                suit: Spade,
+                rank: Two,
+            },
+            Card {
+                suit: Spade,
+                rank: Four,
+            },
+            Card {
+                suit: Spade,
+                rank: Six,
+            },
+            Card {
+                suit: Spade,
+                rank: Nine,
+            },
+        ];
+        let starter = Card {
+            suit: Heart,
+            rank: King,
+        };
+
+        // Fifteens ({6,9} and {2,4,9}) score regardless of is_crib; only the flush is withheld.
+        assert_eq!(score_hand(&hand, starter, false), 8);
+        assert_eq!(score_hand(&hand, starter, true), 4);
+    }
+
+    #[test]
+    fn double_run_with_a_pair() {
+        use Rank::*;
+        use Suit::*;
+
+        let hand = [
+            Card {
+                suit: Spade,
+                rank: Three,
+            },
+            Card {
+                suit: Heart,
+                rank: Three,
+            },
+            Card {
+                suit: Club,
+                rank: Four,
+            },
+            Card {
+                suit: Diamond,
+                rank: Five,
+            },
+        ];
+        let starter = Card {
+            suit: Spade,
+            rank: King,
+        };
+
+        // Fifteens ({5,10} and {3,3,4,5}) = 4, the pair of threes = 2, and a run of 3 doubled
+        // by the pair = 6, for a total of 12.
+        assert_eq!(score_hand(&hand, starter, false), 12);
+    }
+}
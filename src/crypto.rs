@@ -0,0 +1,167 @@
+// Copyright 2019 Barret Rennie
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Password-derived authenticated encryption for the advertisement and session protocols.
+
+// `chacha20poly1305` 0.5's `Key`/`Nonce` construction goes through `GenericArray::from_slice`,
+// which newer `generic-array` releases deprecate in favor of `TryFrom`; there's no non-deprecated
+// equivalent available at the pinned version.
+#![allow(deprecated)]
+
+use std::fmt;
+use std::num::NonZeroU32;
+
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KDF_SALT: &[u8] = b"brennie/cribbage-password-kdf-v1";
+const KDF_ITERATIONS: u32 = 100_000;
+
+/// An error produced while opening a sealed frame.
+#[derive(Debug)]
+pub enum CryptoError {
+    /// The frame was too short to contain a nonce and authentication tag.
+    Truncated,
+    /// Authentication failed: the frame was tampered with, or sealed under a different key.
+    Forged,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::Truncated => write!(f, "frame is too short to be a sealed frame"),
+            CryptoError::Forged => write!(f, "frame failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Derive a 32-byte key from a password using a fixed salt.
+fn derive_key(password: &str) -> [u8; 32] {
+    let mut key = [0u8; 32];
+
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(KDF_ITERATIONS).unwrap(),
+        KDF_SALT,
+        password.as_bytes(),
+        &mut key,
+    );
+
+    key
+}
+
+/// An authenticated, encrypted channel over ChaCha20-Poly1305, keyed by a password.
+///
+/// Sealed frames are laid out as `nonce (12 bytes) || ciphertext || tag`, with a fresh random
+/// nonce generated per frame. The advertisement protocol and the session protocol that follows it
+/// both use this to turn a `ServeOptions::password` from an inert flag into real access control.
+#[derive(Clone)]
+pub struct SecureChannel {
+    key: [u8; 32],
+}
+
+impl SecureChannel {
+    /// Construct a channel by deriving a key from `password`.
+    pub fn from_password(password: &str) -> Self {
+        SecureChannel {
+            key: derive_key(password),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+
+    /// Encrypt and authenticate `plaintext`, returning `nonce || ciphertext || tag`.
+    pub fn seal<R: Rng>(&self, plaintext: &[u8], rng: &mut R) -> Vec<u8> {
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        rng.fill(&mut nonce_bytes);
+
+        let mut sealed = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+            .expect("encryption with a freshly generated nonce cannot fail");
+
+        let mut frame = Vec::with_capacity(NONCE_LEN + sealed.len());
+        frame.extend_from_slice(&nonce_bytes);
+        frame.append(&mut sealed);
+        frame
+    }
+
+    /// Verify and decrypt a frame produced by [`seal`](Self::seal), dropping it if
+    /// authentication fails.
+    pub fn open(&self, frame: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if frame.len() < NONCE_LEN + TAG_LEN {
+            return Err(CryptoError::Truncated);
+        }
+
+        let (nonce_bytes, ciphertext) = frame.split_at(NONCE_LEN);
+
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+            .map_err(|_| CryptoError::Forged)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn seal_then_open_roundtrips() {
+        let channel = SecureChannel::from_password("hunter2");
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let sealed = channel.seal(b"cribbage-advertisement-request", &mut rng);
+
+        assert_eq!(
+            channel.open(&sealed).unwrap(),
+            b"cribbage-advertisement-request"
+        );
+    }
+
+    #[test]
+    fn open_rejects_a_tampered_frame() {
+        let channel = SecureChannel::from_password("hunter2");
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut sealed = channel.seal(b"cribbage-advertisement-request", &mut rng);
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0xff;
+
+        assert!(channel.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn open_rejects_a_frame_sealed_under_a_different_password() {
+        let channel = SecureChannel::from_password("hunter2");
+        let other = SecureChannel::from_password("swordfish");
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let sealed = channel.seal(b"cribbage-advertisement-request", &mut rng);
+
+        assert!(other.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn open_rejects_a_truncated_frame() {
+        let channel = SecureChannel::from_password("hunter2");
+
+        match channel.open(&[0u8; 4]) {
+            Err(CryptoError::Truncated) => {}
+            other => panic!("expected Truncated, got {:?}", other),
+        }
+    }
+}
@@ -6,21 +6,32 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::net::SocketAddr;
+
 use structopt::StructOpt;
+use tokio::prelude::Future;
 
 pub mod card;
+pub mod crypto;
 pub mod net;
+pub mod protocol;
 
 #[derive(Debug, StructOpt)]
 enum Command {
     #[structopt(name = "list")]
-    List,
+    List(ListOptions),
 
     #[structopt(name = "serve")]
     Serve(ServeOptions),
 
     #[structopt(name = "connect")]
-    Connect,
+    Connect(ConnectOptions),
+}
+
+#[derive(Debug, StructOpt)]
+struct ListOptions {
+    #[structopt(long)]
+    password: Option<String>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -33,6 +44,18 @@ struct ServeOptions {
 
     #[structopt(long)]
     password: Option<String>,
+
+    #[structopt(long, default_value = "2")]
+    max_players: u8,
+}
+
+#[derive(Debug, StructOpt)]
+struct ConnectOptions {
+    #[structopt(name = "address")]
+    address: SocketAddr,
+
+    #[structopt(long)]
+    password: Option<String>,
 }
 
 fn main() {
@@ -40,18 +63,53 @@ fn main() {
 
     match cmd {
         Command::Serve(options) => serve(options),
-        Command::List => list(),
-        _ => unimplemented!(),
+        Command::List(options) => list(options),
+        Command::Connect(options) => connect(options),
     }
 }
 
 fn serve(options: ServeOptions) {
-    let adv = net::serve_advertisement(options.name.clone(), options.port, options.password.is_some());
+    // Both seats are taken by connecting clients, so no one is seated yet; the advertised count
+    // is not updated as players join.
+    let adv = net::serve_advertisement(
+        options.name,
+        options.port,
+        options.password.clone(),
+        0,
+        options.max_players,
+    );
+    let session = net::serve_session(options.port, options.password);
+
+    tokio::run(adv.join(session).map(|_| ()));
+}
+
+fn list(options: ListOptions) {
+    let query = net::query_advertisements(options.password).map(print_servers);
+    tokio::run(query);
+}
 
-    tokio::run(adv);
+fn connect(options: ConnectOptions) {
+    tokio::run(net::connect_session(options.address, options.password));
 }
 
-fn list() {
-    let adv = net::query_advertisements();
-    tokio::run(adv);
+fn print_servers(servers: Vec<net::ServerInfo>) {
+    if servers.is_empty() {
+        println!("No games found.");
+        return;
+    }
+
+    println!("{:<20} {:<21} {:>7}", "NAME", "ADDRESS", "PLAYERS");
+
+    for server in servers {
+        let lock = if server.password_required {
+            " 🔒"
+        } else {
+            ""
+        };
+
+        println!(
+            "{:<20} {:<21} {:>3}/{:<3}{}",
+            server.name, server.addr, server.current_players, server.max_players, lock
+        );
+    }
 }
@@ -6,45 +6,118 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::collections::HashMap;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
 
 use bincode;
 use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
-use tokio::codec::LengthDelimitedCodec;
-use tokio::net::{UdpFramed, UdpSocket};
+use tokio::codec::{Framed, LengthDelimitedCodec};
+use tokio::net::{TcpListener, TcpStream, UdpFramed, UdpSocket};
 use tokio::prelude::*;
+use tokio::timer::Delay;
+
+use crate::card::{Card, Deck};
+use crate::crypto::SecureChannel;
+use crate::protocol::{self, ClientMessage, GameState, ServerMessage};
 
 const IP_ALL: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
 const IP_MULTICAST: Ipv4Addr = Ipv4Addr::new(229, 29, 29, 29);
 const ADVERT_PORT: u16 = 29999;
 
-fn multicast_udp_socket(
-    local_addr: &SocketAddrV4,
-) -> io::Result<std::net::UdpSocket> {
+/// The version of the advertisement/session protocol spoken by this build.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How long [`query_advertisements`] waits for responders before returning what it has found.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn multicast_udp_socket(local_addr: &SocketAddrV4) -> io::Result<std::net::UdpSocket> {
     use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 
     let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
 
     socket.set_reuse_address(true)?;
     socket.set_multicast_loop_v4(true)?;
-    socket.join_multicast_v4(&IP_MULTICAST, &local_addr.ip())?;
+    socket.join_multicast_v4(&IP_MULTICAST, local_addr.ip())?;
     socket.bind(&SockAddr::from(*local_addr))?;
 
     Ok(socket.into_udp_socket())
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 struct AdvertisementResponse {
-    magic: &'static str,
+    magic: String,
+    name: String,
+    version: u32,
     port: u16,
+    password_required: bool,
+    current_players: u8,
+    max_players: u8,
+}
+
+const MAGIC_REQUEST: &str = "cribbage-advertisement-request";
+const MAGIC_RESPONSE: &str = "cribbage-advertisement-response";
+
+/// A discovered server, as reported by [`query_advertisements`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub version: u32,
+    pub password_required: bool,
+    pub current_players: u8,
+    pub max_players: u8,
+}
+
+/// Encode a plaintext payload for the wire, sealing it with `channel` if one is set.
+fn encode(channel: &Option<SecureChannel>, rng: &mut StdRng, plaintext: &[u8]) -> Bytes {
+    match channel {
+        Some(channel) => Bytes::from(channel.seal(plaintext, rng)),
+        None => Bytes::from(plaintext.to_vec()),
+    }
+}
+
+/// Decode a frame from the wire, verifying it against `channel` if one is set.
+///
+/// A frame that fails authentication is treated the same as one that was never sent: `None`.
+fn decode(channel: &Option<SecureChannel>, frame: &[u8]) -> Option<Vec<u8>> {
+    match channel {
+        Some(channel) => channel.open(frame).ok(),
+        None => Some(frame.to_vec()),
+    }
+}
+
+/// Serialize `msg` with bincode and encode it for the wire, sealing it with `channel` if one is
+/// set.
+fn encode_message<T: Serialize>(
+    channel: &Option<SecureChannel>,
+    rng: &mut StdRng,
+    msg: &T,
+) -> Bytes {
+    let payload = bincode::serialize(msg).expect("messages are always serializable");
+    encode(channel, rng, &payload)
 }
 
-const MAGIC_REQUEST: &'static str = "cribbage-advertisement-request";
-const MAGIC_RESPONSE: &'static str = "cribbage-advertisement-response";
+/// Decode a frame from the wire and deserialize it with bincode, dropping it (as `None`) if
+/// either step fails.
+fn decode_message<T: for<'de> Deserialize<'de>>(
+    channel: &Option<SecureChannel>,
+    frame: &[u8],
+) -> Option<T> {
+    decode(channel, frame).and_then(|plaintext| bincode::deserialize(&plaintext).ok())
+}
 
-pub fn serve_advertisement(port: u16) -> impl Future<Item = (), Error = ()> {
+pub fn serve_advertisement(
+    name: String,
+    port: u16,
+    password: Option<String>,
+    current_players: u8,
+    max_players: u8,
+) -> impl Future<Item = (), Error = ()> {
     let socket = UdpSocket::from_std(
         multicast_udp_socket(&SocketAddrV4::new(IP_ALL, ADVERT_PORT)).unwrap(),
         &tokio::reactor::Handle::default(),
@@ -53,27 +126,41 @@ pub fn serve_advertisement(port: u16) -> impl Future<Item = (), Error = ()> {
 
     let (tx, rx) = UdpFramed::new(socket, LengthDelimitedCodec::new()).split();
 
-    future::loop_fn((tx, rx), move |(tx, rx)| {
+    let channel = password.as_deref().map(SecureChannel::from_password);
+    let rng = StdRng::from_entropy();
+    let response = AdvertisementResponse {
+        magic: MAGIC_RESPONSE.to_string(),
+        name,
+        version: PROTOCOL_VERSION,
+        port,
+        password_required: channel.is_some(),
+        current_players,
+        max_players,
+    };
+
+    future::loop_fn((tx, rx, channel, rng), move |(tx, rx, channel, mut rng)| {
+        let response = response.clone();
+
         rx.into_future()
             .map_err(drop)
             .and_then(move |(request, rx)| {
                 if let Some((bytes, addr)) = request {
-                    if bytes == MAGIC_REQUEST {
-                        let rsp = Bytes::from(
-                            bincode::serialize(&AdvertisementResponse {
-                                magic: MAGIC_RESPONSE,
-                                port: port,
-                            })
-                            .unwrap(),
-                        );
-
-                        future::Either::A(
-                            tx.send((rsp, addr))
-                                .map_err(drop)
-                                .map(move |tx| future::Loop::Continue((tx, rx))),
-                        )
-                    } else {
-                        future::Either::B(future::ok(future::Loop::Continue((tx, rx))))
+                    // A password-protected server only answers requests that prove knowledge of
+                    // the key; a frame that fails authentication is silently dropped.
+                    match decode(&channel, &bytes) {
+                        Some(ref plaintext) if plaintext == MAGIC_REQUEST.as_bytes() => {
+                            let payload = bincode::serialize(&response).unwrap();
+                            let rsp = encode(&channel, &mut rng, &payload);
+
+                            future::Either::A(
+                                tx.send((rsp, addr))
+                                    .map_err(drop)
+                                    .map(move |tx| future::Loop::Continue((tx, rx, channel, rng))),
+                            )
+                        }
+                        _ => future::Either::B(future::ok(future::Loop::Continue((
+                            tx, rx, channel, rng,
+                        )))),
                     }
                 } else {
                     drop(tx.reunite(rx).unwrap());
@@ -83,37 +170,223 @@ pub fn serve_advertisement(port: u16) -> impl Future<Item = (), Error = ()> {
     })
 }
 
-pub fn query_advertisements() -> impl Future<Item = (), Error = ()> {
-    let local_addr = SocketAddrV4::new(IP_ALL.into(), 1234);
+/// Discover servers for [`DISCOVERY_TIMEOUT`], returning the distinct responders found.
+pub fn query_advertisements(
+    password: Option<String>,
+) -> impl Future<Item = Vec<ServerInfo>, Error = ()> {
+    let local_addr = SocketAddrV4::new(IP_ALL, 1234);
     let multicast_addr = SocketAddr::new(IpAddr::V4(IP_MULTICAST), ADVERT_PORT);
 
     let socket = UdpSocket::from_std(
         multicast_udp_socket(&local_addr).unwrap(),
-        &tokio::reactor::Handle::default()
-    ).unwrap();
+        &tokio::reactor::Handle::default(),
+    )
+    .unwrap();
 
     let (tx, rx) = UdpFramed::new(socket, LengthDelimitedCodec::new()).split();
 
-    tx.send((Bytes::from(MAGIC_REQUEST), multicast_addr))
+    let channel = password.as_deref().map(SecureChannel::from_password);
+    let mut rng = StdRng::from_entropy();
+    let request = encode(&channel, &mut rng, MAGIC_REQUEST.as_bytes());
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+
+    tx.send((request, multicast_addr))
         .map_err(drop)
         .and_then(move |_tx| {
-            future::loop_fn(rx, |rx| {
-                rx
-                    .into_future()
-                    .map_err(drop)
-                    .map(|(rsp, rx)| {
-                        if let Some((rsp, addr)) = rsp {
-                            match bincode::deserialize::<AdvertisementResponse>(&rsp) {
-                                Ok(a) => drop(a),
-                                Err(e) => drop(e),
-                            }
-
-                            future::Loop::Continue(rx)
-                        } else {
-                            future::Loop::Break(())
+            future::loop_fn(
+                (rx, channel, HashMap::new()),
+                move |(rx, channel, mut found): (_, _, HashMap<SocketAddr, ServerInfo>)| {
+                    rx.into_future()
+                        .map_err(drop)
+                        .select2(Delay::new(deadline).map_err(drop))
+                        .map_err(|_| ())
+                        .map(move |outcome| match outcome {
+                            future::Either::A(((response, rx), _timeout)) => match response {
+                                Some((frame, addr)) => {
+                                    if let Some(plaintext) = decode(&channel, &frame) {
+                                        if let Ok(rsp) = bincode::deserialize::<AdvertisementResponse>(&plaintext) {
+                                            found.entry(addr).or_insert(ServerInfo {
+                                                name: rsp.name,
+                                                addr,
+                                                version: rsp.version,
+                                                password_required: rsp.password_required,
+                                                current_players: rsp.current_players,
+                                                max_players: rsp.max_players,
+                                            });
+                                        }
+                                    }
+
+                                    future::Loop::Continue((rx, channel, found))
+                                }
+                                None => future::Loop::Break(found),
+                            },
+                            future::Either::B(_) => future::Loop::Break(found),
+                        })
+                },
+            )
+        })
+        .map(|found| found.into_values().collect())
+}
+
+type SessionSink = stream::SplitSink<Framed<TcpStream, LengthDelimitedCodec>>;
+
+/// Accept the classic two-player game on `port`: deal a hand, then drive play via
+/// [`protocol::apply`] until the hand ends.
+///
+/// This only wires up two players; the three- and four-player layouts [`Deck::deal_n`] supports
+/// are not yet reachable over the network. If `password` is set, the session reuses the same
+/// [`SecureChannel`] the advertisement protocol does, so a locked server's session traffic is
+/// sealed the same way its discovery traffic is.
+pub fn serve_session(port: u16, password: Option<String>) -> impl Future<Item = (), Error = ()> {
+    let listener = TcpListener::bind(&SocketAddr::new(IpAddr::V4(IP_ALL), port))
+        .expect("could not bind session listener");
+    let channel = password.as_deref().map(SecureChannel::from_password);
+
+    listener
+        .incoming()
+        .map_err(drop)
+        .take(2)
+        .collect()
+        .and_then(move |sockets| {
+            let mut rng = StdRng::from_entropy();
+            let mut deck = Deck::new_shuffled(&mut rng);
+            let (dealt, _crib) = deck
+                .deal_n(2)
+                .expect("2 players is always a supported player count");
+            let hands: Vec<Vec<Card>> = dealt.iter().map(|h| h.cards().to_vec()).collect();
+            let dealer = 0;
+            let state = GameState::new(hands.clone(), dealer, 4, deck.cards().to_vec());
+
+            let mut sockets = sockets.into_iter();
+            let (sink0, stream0) =
+                Framed::new(sockets.next().unwrap(), LengthDelimitedCodec::new()).split();
+            let (sink1, stream1) =
+                Framed::new(sockets.next().unwrap(), LengthDelimitedCodec::new()).split();
+
+            let frame0 = encode_message(
+                &channel,
+                &mut rng,
+                &ServerMessage::DealHands {
+                    your_hand: hands[0].clone(),
+                    dealer,
+                },
+            );
+            let frame1 = encode_message(
+                &channel,
+                &mut rng,
+                &ServerMessage::DealHands {
+                    your_hand: hands[1].clone(),
+                    dealer,
+                },
+            );
+
+            sink0
+                .send(frame0)
+                .join(sink1.send(frame1))
+                .map_err(drop)
+                .and_then(move |(sink0, sink1)| {
+                    let tagged0 = stream0.map_err(drop).filter_map({
+                        let channel = channel.clone();
+                        move |frame| {
+                            decode_message::<ClientMessage>(&channel, &frame)
+                                .map(|msg| (0usize, msg))
                         }
-                    })
-            })
+                    });
+                    let tagged1 = stream1.map_err(drop).filter_map({
+                        let channel = channel.clone();
+                        move |frame| {
+                            decode_message::<ClientMessage>(&channel, &frame)
+                                .map(|msg| (1usize, msg))
+                        }
+                    });
+                    let merged = tagged0.select(tagged1);
+
+                    future::loop_fn(
+                        (sink0, sink1, merged, rng, state),
+                        move |(sink0, sink1, merged, rng, mut state)| {
+                            let channel = channel.clone();
+
+                            merged.into_future().map_err(|(e, _)| e).and_then(
+                                move |(item, merged)| match item {
+                                    Some((player, msg)) => {
+                                        let messages = protocol::apply(&mut state, player, &msg)
+                                            .unwrap_or_default();
+                                        let hand_over = state.phase == protocol::Phase::Show
+                                            || messages.contains(&ServerMessage::GameOver);
+
+                                        future::Either::A(
+                                            broadcast(sink0, sink1, rng, channel, messages).map(
+                                                move |(sink0, sink1, rng)| {
+                                                    if hand_over {
+                                                        future::Loop::Break(())
+                                                    } else {
+                                                        future::Loop::Continue((
+                                                            sink0, sink1, merged, rng, state,
+                                                        ))
+                                                    }
+                                                },
+                                            ),
+                                        )
+                                    }
+                                    None => future::Either::B(future::ok(future::Loop::Break(()))),
+                                },
+                            )
+                        },
+                    )
+                })
         })
-        .map(drop)
-}
\ No newline at end of file
+}
+
+/// Send every message in `messages`, in order, to both `sink0` and `sink1`, sealing each with
+/// `channel` if one is set.
+fn broadcast(
+    sink0: SessionSink,
+    sink1: SessionSink,
+    rng: StdRng,
+    channel: Option<SecureChannel>,
+    messages: Vec<ServerMessage>,
+) -> impl Future<Item = (SessionSink, SessionSink, StdRng), Error = ()> {
+    future::loop_fn(
+        (sink0, sink1, rng, messages.into_iter()),
+        move |(sink0, sink1, mut rng, mut remaining)| match remaining.next() {
+            Some(msg) => {
+                let frame = encode_message(&channel, &mut rng, &msg);
+
+                future::Either::A(
+                    sink0
+                        .send(frame.clone())
+                        .join(sink1.send(frame))
+                        .map_err(drop)
+                        .map(move |(sink0, sink1)| {
+                            future::Loop::Continue((sink0, sink1, rng, remaining))
+                        }),
+                )
+            }
+            None => future::Either::B(future::ok(future::Loop::Break((sink0, sink1, rng)))),
+        },
+    )
+}
+
+/// Connect to a game session at `addr` and print every [`ServerMessage`] broadcast by the
+/// server.
+///
+/// This is a minimal client: it proves the session codec round-trips correctly, but it does not
+/// yet let the player act — [`ClientMessage`]s are never sent. If `password` is set, it reuses
+/// the same [`SecureChannel`] the advertisement protocol uses to reach a locked server.
+pub fn connect_session(
+    addr: SocketAddr,
+    password: Option<String>,
+) -> impl Future<Item = (), Error = ()> {
+    let channel = password.as_deref().map(SecureChannel::from_password);
+
+    TcpStream::connect(&addr).map_err(drop).and_then(|socket| {
+        Framed::new(socket, LengthDelimitedCodec::new())
+            .map_err(drop)
+            .for_each(move |frame| {
+                if let Some(msg) = decode_message::<ServerMessage>(&channel, &frame) {
+                    println!("{:?}", msg);
+                }
+                Ok(())
+            })
+    })
+}
@@ -0,0 +1,505 @@
+// Copyright 2019 Barret Rennie
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Messages exchanged during a cribbage game, and the reducer that validates them.
+//!
+//! The protocol is split into the actions a client may request ([`ClientMessage`]) and the
+//! events a server broadcasts in response ([`ServerMessage`]). [`apply`] is the single
+//! authoritative reducer over [`GameState`]: both `serve` and an eventual bot opponent drive the
+//! game through it, so the rules only need to be encoded once.
+//!
+//! [`apply`] only enforces the legality of actions (turn order, the 31-count limit, and legal
+//! discards); it does not yet award pegging or show points. [`ServerMessage::ScoreUpdate`]
+//! exists for a future scoring pass to report points through, built on [`crate::card::score`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::card::Card;
+
+/// A message sent from a client to the server.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ClientMessage {
+    /// Discard two cards to the crib.
+    Discard([Card; 2]),
+
+    /// Play a card during the play phase.
+    PlayCard(Card),
+
+    /// Declare that no card in hand can be played without exceeding 31.
+    Go,
+
+    /// Cut the deck for the starter once everyone has discarded, beginning the play phase.
+    Cut,
+}
+
+/// A message broadcast from the server to all clients.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ServerMessage {
+    /// The hands have been dealt; `your_hand` is specific to the recipient.
+    DealHands { your_hand: Vec<Card>, dealer: usize },
+
+    /// The starter card has been cut.
+    Starter(Card),
+
+    /// A player played a card during the play phase.
+    Played {
+        player: usize,
+        card: Card,
+        running_total: u32,
+    },
+
+    /// A player's score changed, and why.
+    ///
+    /// Not yet sent by [`apply`]; reserved for a future pegging/show scoring pass.
+    ScoreUpdate {
+        player: usize,
+        points: u32,
+        reason: String,
+    },
+
+    /// The game has ended.
+    GameOver,
+}
+
+/// An error produced when a [`ClientMessage`] is not legal given the current [`GameState`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProtocolError {
+    /// The message was sent out of phase (e.g. a `PlayCard` during the discard phase).
+    WrongPhase,
+
+    /// It is not this player's turn to act.
+    NotYourTurn,
+
+    /// The player's hand does not contain the referenced card.
+    CardNotInHand,
+
+    /// A discard named the same card twice, which cannot be satisfied from a single hand.
+    DuplicateDiscard,
+
+    /// Playing this card would push the count past 31.
+    CountExceeds31,
+
+    /// A `Go` was declared even though a legal card could be played.
+    IllegalGo,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::WrongPhase => write!(f, "message is not legal in the current phase"),
+            ProtocolError::NotYourTurn => write!(f, "it is not your turn"),
+            ProtocolError::CardNotInHand => write!(f, "that card is not in your hand"),
+            ProtocolError::DuplicateDiscard => {
+                write!(f, "the same card cannot be discarded twice")
+            }
+            ProtocolError::CountExceeds31 => write!(f, "playing that card would exceed 31"),
+            ProtocolError::IllegalGo => write!(f, "a legal card can still be played"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// The phase of a single deal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Players are discarding to the crib.
+    Discard,
+    /// The non-dealer is cutting the deck for the starter.
+    Cut,
+    /// Players are pegging, taking turns playing cards toward a count of 31.
+    Play,
+    /// The deal is over and hands are being counted.
+    Show,
+}
+
+/// The authoritative state of a game in progress, shared by the server and any bot player.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GameState {
+    /// Each player's cards that have not yet been discarded or played.
+    pub hands: Vec<Vec<Card>>,
+    /// Cards discarded to the crib so far.
+    pub crib: Vec<Card>,
+    /// The index of the dealer for this deal.
+    pub dealer: usize,
+    /// The index of the player whose turn it is to act.
+    pub turn: usize,
+    /// The current phase of the deal.
+    pub phase: Phase,
+    /// The running total of pip values played so far this round of the play phase.
+    pub play_count: u32,
+    /// `(player, card)` pairs in the order they were played this round.
+    pub play_history: Vec<(usize, Card)>,
+    /// The hand size each player discards down to before the play phase.
+    pub target_hand_size: usize,
+    /// The cards remaining in the deck once hands (and, for three players, the crib) are dealt,
+    /// in cut order: the last card is the next one [`apply_cut`] will reveal as the starter.
+    pub deck: Vec<Card>,
+    /// The starter card, once [`ClientMessage::Cut`] has been applied.
+    pub starter: Option<Card>,
+}
+
+impl GameState {
+    /// Begin a new deal with `hands` already dealt, `dealer` as the dealer, and `deck` holding
+    /// whatever cards remain to cut the starter from.
+    ///
+    /// Discards may be made in any order. The player left of the dealer cuts for the starter and,
+    /// once the play phase begins, leads.
+    pub fn new(
+        hands: Vec<Vec<Card>>,
+        dealer: usize,
+        target_hand_size: usize,
+        deck: Vec<Card>,
+    ) -> Self {
+        let turn = (dealer + 1) % hands.len();
+
+        GameState {
+            hands,
+            crib: Vec::new(),
+            dealer,
+            turn,
+            phase: Phase::Discard,
+            play_count: 0,
+            play_history: Vec::new(),
+            target_hand_size,
+            deck,
+            starter: None,
+        }
+    }
+
+    fn player_count(&self) -> usize {
+        self.hands.len()
+    }
+
+    /// Return whether `player` has a card left that would not push the count past 31.
+    fn has_legal_play(&self, player: usize) -> bool {
+        self.hands[player]
+            .iter()
+            .any(|c| self.play_count + c.rank.pip_value() <= 31)
+    }
+}
+
+/// Apply a [`ClientMessage`] sent by `player` to `state`, returning the resulting broadcasts.
+///
+/// This is the single place turn order, the 31-count limit, and legal discards are enforced;
+/// `state` is only mutated if the message is legal.
+pub fn apply(
+    state: &mut GameState,
+    player: usize,
+    msg: &ClientMessage,
+) -> Result<Vec<ServerMessage>, ProtocolError> {
+    match msg {
+        ClientMessage::Discard(cards) => apply_discard(state, player, cards),
+        ClientMessage::Cut => apply_cut(state, player),
+        ClientMessage::PlayCard(card) => apply_play_card(state, player, *card),
+        ClientMessage::Go => apply_go(state, player),
+    }
+}
+
+fn apply_discard(
+    state: &mut GameState,
+    player: usize,
+    cards: &[Card; 2],
+) -> Result<Vec<ServerMessage>, ProtocolError> {
+    if state.phase != Phase::Discard {
+        return Err(ProtocolError::WrongPhase);
+    }
+
+    if cards[0] == cards[1] {
+        return Err(ProtocolError::DuplicateDiscard);
+    }
+
+    for card in cards {
+        if !state.hands[player].contains(card) {
+            return Err(ProtocolError::CardNotInHand);
+        }
+    }
+
+    for card in cards {
+        let index = state.hands[player]
+            .iter()
+            .position(|c| c == card)
+            .expect("presence already checked above");
+        state.hands[player].remove(index);
+        state.crib.push(*card);
+    }
+
+    if state
+        .hands
+        .iter()
+        .all(|h| h.len() <= state.target_hand_size)
+    {
+        state.phase = Phase::Cut;
+        state.turn = (state.dealer + 1) % state.player_count();
+    }
+
+    Ok(Vec::new())
+}
+
+fn apply_cut(state: &mut GameState, player: usize) -> Result<Vec<ServerMessage>, ProtocolError> {
+    if state.phase != Phase::Cut {
+        return Err(ProtocolError::WrongPhase);
+    }
+
+    if player != state.turn {
+        return Err(ProtocolError::NotYourTurn);
+    }
+
+    let starter = state
+        .deck
+        .pop()
+        .expect("enough cards remain in the deck to cut a starter");
+    state.starter = Some(starter);
+
+    state.phase = Phase::Play;
+    state.play_count = 0;
+    state.play_history.clear();
+
+    Ok(vec![ServerMessage::Starter(starter)])
+}
+
+fn apply_play_card(
+    state: &mut GameState,
+    player: usize,
+    card: Card,
+) -> Result<Vec<ServerMessage>, ProtocolError> {
+    if state.phase != Phase::Play {
+        return Err(ProtocolError::WrongPhase);
+    }
+
+    if player != state.turn {
+        return Err(ProtocolError::NotYourTurn);
+    }
+
+    let index = state.hands[player]
+        .iter()
+        .position(|c| *c == card)
+        .ok_or(ProtocolError::CardNotInHand)?;
+
+    let pip_value = card.rank.pip_value();
+    if state.play_count + pip_value > 31 {
+        return Err(ProtocolError::CountExceeds31);
+    }
+
+    state.hands[player].remove(index);
+    state.play_count += pip_value;
+    state.play_history.push((player, card));
+
+    let mut messages = vec![ServerMessage::Played {
+        player,
+        card,
+        running_total: state.play_count,
+    }];
+
+    advance_turn_after_play(state, &mut messages);
+
+    Ok(messages)
+}
+
+fn apply_go(state: &mut GameState, player: usize) -> Result<Vec<ServerMessage>, ProtocolError> {
+    if state.phase != Phase::Play {
+        return Err(ProtocolError::WrongPhase);
+    }
+
+    if player != state.turn {
+        return Err(ProtocolError::NotYourTurn);
+    }
+
+    if state.has_legal_play(player) {
+        return Err(ProtocolError::IllegalGo);
+    }
+
+    let mut messages = Vec::new();
+    advance_turn_after_play(state, &mut messages);
+
+    Ok(messages)
+}
+
+/// Move `turn` on to the next player who can still act, resetting the count (and, once every
+/// hand is empty, ending the play phase) when nobody can.
+fn advance_turn_after_play(state: &mut GameState, _messages: &mut Vec<ServerMessage>) {
+    let n = state.player_count();
+
+    if state.hands.iter().all(|h| h.is_empty()) {
+        state.phase = Phase::Show;
+        return;
+    }
+
+    for offset in 1..=n {
+        let candidate = (state.turn + offset) % n;
+        if !state.hands[candidate].is_empty() && state.has_legal_play(candidate) {
+            state.turn = candidate;
+            return;
+        }
+    }
+
+    // Nobody at the table can play without exceeding 31: the count resets and play continues
+    // with the next player who still holds cards.
+    state.play_count = 0;
+    state.play_history.clear();
+
+    for offset in 1..=n {
+        let candidate = (state.turn + offset) % n;
+        if !state.hands[candidate].is_empty() {
+            state.turn = candidate;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::card::{Rank, Suit};
+
+    fn card(suit: Suit, rank: Rank) -> Card {
+        Card { suit, rank }
+    }
+
+    #[test]
+    fn discard_transitions_to_cut_once_everyone_has_discarded() {
+        use Rank::*;
+        use Suit::*;
+
+        let hands = vec![
+            vec![
+                card(Spade, Ace),
+                card(Spade, Two),
+                card(Spade, Three),
+                card(Spade, Four),
+                card(Spade, Five),
+                card(Spade, Six),
+            ],
+            vec![
+                card(Heart, Ace),
+                card(Heart, Two),
+                card(Heart, Three),
+                card(Heart, Four),
+                card(Heart, Five),
+                card(Heart, Six),
+            ],
+        ];
+        let mut state = GameState::new(hands, 0, 4, Vec::new());
+
+        apply(
+            &mut state,
+            0,
+            &ClientMessage::Discard([card(Spade, Five), card(Spade, Six)]),
+        )
+        .unwrap();
+        assert_eq!(state.phase, Phase::Discard);
+
+        apply(
+            &mut state,
+            1,
+            &ClientMessage::Discard([card(Heart, Five), card(Heart, Six)]),
+        )
+        .unwrap();
+        assert_eq!(state.phase, Phase::Cut);
+        assert_eq!(state.crib.len(), 4);
+    }
+
+    #[test]
+    fn discard_rejects_the_same_card_named_twice() {
+        use Rank::*;
+        use Suit::*;
+
+        let hands = vec![
+            vec![
+                card(Spade, Ace),
+                card(Spade, Two),
+                card(Spade, Three),
+                card(Spade, Four),
+                card(Spade, Five),
+                card(Spade, Six),
+            ],
+            vec![
+                card(Heart, Ace),
+                card(Heart, Two),
+                card(Heart, Three),
+                card(Heart, Four),
+                card(Heart, Five),
+                card(Heart, Six),
+            ],
+        ];
+        let mut state = GameState::new(hands, 0, 4, Vec::new());
+
+        assert_eq!(
+            apply(
+                &mut state,
+                0,
+                &ClientMessage::Discard([card(Spade, Five), card(Spade, Five)]),
+            ),
+            Err(ProtocolError::DuplicateDiscard)
+        );
+        // The rejected discard must not have mutated the hand.
+        assert_eq!(state.hands[0].len(), 6);
+    }
+
+    #[test]
+    fn cut_reveals_the_starter_and_begins_play() {
+        use Rank::*;
+        use Suit::*;
+
+        let hands = vec![vec![card(Spade, Ace)], vec![card(Heart, Ace)]];
+        let deck = vec![card(Diamond, Seven), card(Club, King)];
+        let mut state = GameState::new(hands, 0, 0, deck);
+        state.phase = Phase::Cut;
+        state.turn = 1;
+
+        // It is player 1's turn to cut, not player 0's.
+        assert_eq!(
+            apply(&mut state, 0, &ClientMessage::Cut),
+            Err(ProtocolError::NotYourTurn)
+        );
+
+        // The starter is the top of the deck, leaving the rest behind.
+        assert_eq!(
+            apply(&mut state, 1, &ClientMessage::Cut),
+            Ok(vec![ServerMessage::Starter(card(Club, King))])
+        );
+        assert_eq!(state.starter, Some(card(Club, King)));
+        assert_eq!(state.deck, vec![card(Diamond, Seven)]);
+        assert_eq!(state.phase, Phase::Play);
+    }
+
+    #[test]
+    fn play_card_enforces_turn_order_and_the_count_limit() {
+        use Rank::*;
+        use Suit::*;
+
+        let hands = vec![
+            vec![card(Spade, King)],
+            vec![card(Heart, King), card(Heart, Queen)],
+        ];
+        let mut state = GameState::new(hands, 0, 0, Vec::new());
+        state.phase = Phase::Play;
+        state.turn = 1;
+
+        // It is player 1's turn, not player 0's.
+        assert_eq!(
+            apply(&mut state, 0, &ClientMessage::PlayCard(card(Spade, King))),
+            Err(ProtocolError::NotYourTurn)
+        );
+
+        apply(&mut state, 1, &ClientMessage::PlayCard(card(Heart, King))).unwrap();
+        assert_eq!(state.play_count, 10);
+        assert_eq!(state.turn, 0);
+
+        // 10 (already played) + 10 (king) = 20, legal.
+        apply(&mut state, 0, &ClientMessage::PlayCard(card(Spade, King))).unwrap();
+        assert_eq!(state.play_count, 20);
+
+        // 20 + 10 (queen) = 30, still legal, but would leave no one able to play further.
+        apply(&mut state, 1, &ClientMessage::PlayCard(card(Heart, Queen))).unwrap();
+        assert_eq!(state.play_count, 30);
+        assert_eq!(state.phase, Phase::Show);
+    }
+}